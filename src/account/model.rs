@@ -7,6 +7,7 @@
  * data serialisation.
  */
 
+use std::collections::HashMap;
 use std::error;
 use std::num::FpCategory;
 use std::convert::TryFrom;
@@ -20,6 +21,22 @@ pub enum Error {
 
     /// Not enough funds for processing transactions.
     InsufficientFunds,
+
+    /// A dispute, resolve or chargeback named a transaction this client
+    /// never made.
+    UnknownTx(Tx),
+
+    /// A dispute was raised against a transaction that is already under
+    /// dispute, resolved, or charged back.
+    AlreadyDisputed(Tx),
+
+    /// A resolve or chargeback named a transaction that is not currently
+    /// under dispute.
+    NotDisputed(Tx),
+
+    /// The account is locked, following a chargeback, and can no longer
+    /// accept deposits or withdrawals.
+    AccountLocked(ClientId),
 }
 
 impl fmt::Display for Error {
@@ -27,6 +44,10 @@ impl fmt::Display for Error {
         match self {
             Error::Arithmetic => write!(fmt, "Arithmetic error"),
             Error::InsufficientFunds => write!(fmt, "Funds Insufficient for operation"),
+            Error::UnknownTx(tx) => write!(fmt, "Transaction {} is unknown", tx.0),
+            Error::AlreadyDisputed(tx) => write!(fmt, "Transaction {} is already disputed", tx.0),
+            Error::NotDisputed(tx) => write!(fmt, "Transaction {} is not under dispute", tx.0),
+            Error::AccountLocked(client) => write!(fmt, "Account {} is locked", client.0),
         }
     }
 }
@@ -135,18 +156,48 @@ impl From<u32> for Tx {
     }
 }
 
+/// Identifier of an asset/currency a client can hold a balance in.
+///
+/// Hold-a-Coin holds more than one asset per client, so every balance is
+/// keyed by one of these. Transactions from before this crate supported
+/// multiple currencies, or whose `currency` column is absent, use the
+/// implicit base coin returned by `CurrencyId::base`.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct CurrencyId(String);
+
+impl CurrencyId {
+    /// The base coin, used when a transaction omits its currency.
+    pub fn base() -> Self {
+        CurrencyId("COIN".to_string())
+    }
+}
+
+impl fmt::Display for CurrencyId {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(fmt)
+    }
+}
+
+impl From<String> for CurrencyId {
+    fn from(id: String) -> CurrencyId {
+        CurrencyId(id)
+    }
+}
+
 /// The extant types of transactions.
 #[derive(Debug, Eq, PartialEq)]
 pub enum Transaction {
     /// A credit to the client's asset account.
     ///
-    /// It has an identifier and the amount that was deposited.
-    Deposit(Tx, Amount),
+    /// It has an identifier, the amount that was deposited, and the
+    /// currency it was deposited in.
+    Deposit(Tx, Amount, CurrencyId),
 
     /// A debit to the client's asset account.
     ///
-    /// It has an identifier and the amount that was withdrawn.
-    Withdrawal(Tx, Amount),
+    /// It has an identifier, the amount that was withdrawn, and the
+    /// currency it was withdrawn in.
+    Withdrawal(Tx, Amount, CurrencyId),
 
     /// A client's claim that a transaction was erroneous.
     ///
@@ -177,22 +228,57 @@ pub struct ClientTransaction {
     transaction: Transaction,
 }
 
-/// A deposit stored in the client's account.
+/// The state of a recorded transaction through the dispute lifecycle.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TxState {
+    /// Processed normally, and not (currently) under dispute.
+    Processed,
+
+    /// Currently under dispute; its amount is held.
+    Disputed,
+
+    /// A dispute on this transaction was resolved without a chargeback;
+    /// the held amount was released and the transaction stands.
+    Resolved,
+
+    /// A dispute on this transaction ended in a chargeback.
+    ChargedBack,
+}
+
+/// The direction of an amount-bearing transaction, needed to pick the
+/// right dispute math when it is later disputed.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
-struct Deposit {
-    /// The identifier of the transaction for this deposit.
+enum TxDirection {
+    /// Credited to the client's available funds.
+    Credit,
+
+    /// Debited from the client's available funds.
+    Debit,
+}
+
+/// A previously applied deposit or withdrawal, kept around so that a
+/// later dispute/resolve/chargeback can be processed against it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct RecordedTx {
+    /// The identifier of this transaction.
     tx: Tx,
 
-    /// The amount that was deposited.
+    /// The amount that was credited or debited.
     amount: Amount,
 
-    /// If this deposit is currently being disputed.
-    disputed: bool,
+    /// The currency the amount is denominated in.
+    currency: CurrencyId,
+
+    /// Whether this was a deposit or a withdrawal.
+    direction: TxDirection,
+
+    /// Where this transaction is in the dispute lifecycle.
+    state: TxState,
 }
 
-impl Deposit {
-    fn new(tx: Tx, amount: Amount) -> Self {
-        Deposit { tx, amount, disputed: false }
+impl RecordedTx {
+    fn new(tx: Tx, amount: Amount, currency: CurrencyId, direction: TxDirection) -> Self {
+        RecordedTx { tx, amount, currency, direction, state: TxState::Processed }
     }
 }
 
@@ -202,85 +288,158 @@ pub struct Account {
     /// The owner of this account.
     pub owner: ClientId,
 
-    /// The available funds.
-    pub available: Amount,
+    /// The available funds, per currency.
+    available: HashMap<CurrencyId, Amount>,
 
-    /// Funds held in disputes.
-    pub held: Amount,
+    /// Funds held in disputes, per currency.
+    held: HashMap<CurrencyId, Amount>,
 
     /// If the account is locked due to a chargeback.
     pub locked: bool,
 
-    /// Log of deposits
-    deposits: Vec<Deposit>,
+    /// Recorded deposits and withdrawals, indexed by transaction id for
+    /// O(1) dispute lookups.
+    transactions: HashMap<Tx, RecordedTx>,
 }
 
 impl Account {
     pub fn new(client_id: ClientId) -> Self {
+        let mut available = HashMap::new();
+        // Seed the base coin so that a client who exists but never had a
+        // successful deposit/withdrawal (e.g. only a rejected dispute)
+        // still gets a row in the output report.
+        available.insert(CurrencyId::base(), Amount::new());
+
         Account {
             owner: client_id,
-            available: Amount::new(),
-            held: Amount::new(),
+            available,
+            held: HashMap::new(),
             locked: false,
-            deposits: vec![],
+            transactions: HashMap::new(),
         }
     }
 
     pub fn apply(&mut self, tx: Transaction) -> Result<()> {
+        if self.locked {
+            match tx {
+                Transaction::Deposit(..) | Transaction::Withdrawal(..) => {
+                    return Err(Error::AccountLocked(self.owner));
+                },
+                _ => (),
+            }
+        }
+
         match tx {
-            Transaction::Deposit(id, amount) => {
-                let new_avail = self.available.add(amount)?;
-                self.available = new_avail;
-                self.deposits.push(Deposit::new(id, amount));
+            Transaction::Deposit(id, amount, currency) => {
+                let balance = self.available.get(&currency).copied().unwrap_or_else(Amount::new);
+                let new_balance = balance.add(amount)?;
+                self.available.insert(currency.clone(), new_balance);
+                self.transactions.insert(id, RecordedTx::new(id, amount, currency, TxDirection::Credit));
                 Ok(())
             },
-           Transaction::Withdrawal(_, amount) => {
-                let new_avail = self.available.sub(amount).map_err(|_| Error::InsufficientFunds)?;
-                self.available = new_avail;
+           Transaction::Withdrawal(id, amount, currency) => {
+                let balance = self.available.get(&currency).copied().unwrap_or_else(Amount::new);
+                let new_balance = balance.sub(amount).map_err(|_| Error::InsufficientFunds)?;
+                self.available.insert(currency.clone(), new_balance);
+                self.transactions.insert(id, RecordedTx::new(id, amount, currency, TxDirection::Debit));
                 Ok(())
             },
+            // A dispute provisionally moves the disputed amount into
+            // `held`, pending a resolve or chargeback. A disputed deposit
+            // is pulled back out of `available`, since the client claims
+            // it should never have been credited. A disputed withdrawal
+            // instead adds to `held` without touching `available`: the
+            // funds already left the account, so `held` here represents
+            // a potential clawback *into* the account, not money already
+            // there being frozen.
             Transaction::Dispute(other_tx) => {
-                let maybe_dep = self.deposits.iter_mut().find(|d| d.tx == other_tx);
-                match maybe_dep {
-                    Some(dep) if !dep.disputed => {
-                        let new_avail = self.available.sub(dep.amount).map_err(|_| Error::InsufficientFunds)?;
-                        let new_held = self.held.add(dep.amount)?;
-                        self.available = new_avail;
-                        self.held = new_held;
-                        dep.disputed = true;
+                let maybe_rec = self.transactions.get_mut(&other_tx);
+                match maybe_rec {
+                    Some(rec) if rec.state == TxState::Processed => {
+                        let currency = rec.currency.clone();
+                        let amount = rec.amount;
+                        if rec.direction == TxDirection::Credit {
+                            let balance = self.available.get(&currency).copied().unwrap_or_else(Amount::new);
+                            let new_balance = balance.sub(amount).map_err(|_| Error::InsufficientFunds)?;
+                            self.available.insert(currency.clone(), new_balance);
+                        }
+                        let held_balance = self.held.get(&currency).copied().unwrap_or_else(Amount::new);
+                        let new_held = held_balance.add(amount)?;
+                        self.held.insert(currency, new_held);
+                        rec.state = TxState::Disputed;
                         Ok(())
                     },
-                    _ => Ok(())
+                    Some(rec) => Err(Error::AlreadyDisputed(rec.tx)),
+                    None => Err(Error::UnknownTx(other_tx)),
                 }
             },
             Transaction::Resolve(other_tx) => {
-                let maybe_dep = self.deposits.iter_mut().find(|d| d.tx == other_tx);
-                match maybe_dep {
-                    Some(dep) if dep.disputed => {
-                        let new_held = self.held.sub(dep.amount).map_err(|_| Error::InsufficientFunds)?;
-                        let new_avail = self.available.add(dep.amount)?;
-                        self.available = new_avail;
-                        self.held = new_held;
-                        dep.disputed = false;
+                let maybe_rec = self.transactions.get_mut(&other_tx);
+                match maybe_rec {
+                    Some(rec) if rec.state == TxState::Disputed => {
+                        let currency = rec.currency.clone();
+                        let amount = rec.amount;
+                        let held_balance = self.held.get(&currency).copied().unwrap_or_else(Amount::new);
+                        let new_held = held_balance.sub(amount).map_err(|_| Error::InsufficientFunds)?;
+                        self.held.insert(currency.clone(), new_held);
+                        if rec.direction == TxDirection::Credit {
+                            let balance = self.available.get(&currency).copied().unwrap_or_else(Amount::new);
+                            let new_balance = balance.add(amount)?;
+                            self.available.insert(currency, new_balance);
+                        }
+                        rec.state = TxState::Resolved;
                         Ok(())
                     },
-                    _ => Ok(())
+                    Some(rec) => Err(Error::NotDisputed(rec.tx)),
+                    None => Err(Error::UnknownTx(other_tx)),
                 }
             },
+            // A chargeback is the dispute being upheld: a disputed
+            // deposit is reversed and simply drops out of `held` (the
+            // funds leave the platform), while a disputed withdrawal is
+            // reversed by crediting the held amount back into
+            // `available` (the funds return to the client).
             Transaction::Chargeback(other_tx) => {
-                let maybe_dep = self.deposits.iter().find(|d| d.tx == other_tx);
-                match maybe_dep {
-                    Some(dep) if dep.disputed => {
-                        let new_held = self.held.sub(dep.amount).map_err(|_| Error::InsufficientFunds)?;
-                        self.held = new_held;
+                let maybe_rec = self.transactions.get_mut(&other_tx);
+                match maybe_rec {
+                    Some(rec) if rec.state == TxState::Disputed => {
+                        let currency = rec.currency.clone();
+                        let amount = rec.amount;
+                        let held_balance = self.held.get(&currency).copied().unwrap_or_else(Amount::new);
+                        let new_held = held_balance.sub(amount).map_err(|_| Error::InsufficientFunds)?;
+                        self.held.insert(currency.clone(), new_held);
+                        if rec.direction == TxDirection::Debit {
+                            let balance = self.available.get(&currency).copied().unwrap_or_else(Amount::new);
+                            let new_balance = balance.add(amount)?;
+                            self.available.insert(currency, new_balance);
+                        }
                         self.locked = true;
+                        rec.state = TxState::ChargedBack;
                         Ok(())
                     },
-                    _ => Ok(())
+                    Some(rec) => Err(Error::NotDisputed(rec.tx)),
+                    None => Err(Error::UnknownTx(other_tx)),
                 }
             },
         }
     }
+
+    /// Returns the available and held balance for every currency this
+    /// account has a balance in.
+    pub fn balances(&self) -> Vec<(CurrencyId, Amount, Amount)> {
+        let mut currencies: Vec<CurrencyId> = self.available.keys().cloned().collect();
+        for currency in self.held.keys() {
+            if !currencies.contains(currency) {
+                currencies.push(currency.clone());
+            }
+        }
+
+        currencies.into_iter().map(|currency| {
+            let available = self.available.get(&currency).copied().unwrap_or_else(Amount::new);
+            let held = self.held.get(&currency).copied().unwrap_or_else(Amount::new);
+            (currency, available, held)
+        }).collect()
+    }
 }
 
 #[cfg(test)]
@@ -347,4 +506,225 @@ mod test {
             TestResult::from_bool(subbed == zero)
         }
     }
+
+    /// Reads back the (available, held) balance of the base coin.
+    fn base_balance(acc: &Account) -> (Amount, Amount) {
+        acc.balances().into_iter()
+            .find(|(currency, _, _)| *currency == CurrencyId::base())
+            .map(|(_, available, held)| (available, held))
+            .unwrap_or((Amount::new(), Amount::new()))
+    }
+
+    #[test]
+    fn test_locked_account_rejects_deposit() {
+        let mut acc = Account::new(ClientId::from(1));
+        let amount = Amount::try_from(10.0).unwrap();
+        acc.apply(Transaction::Deposit(Tx::from(1), amount, CurrencyId::base())).unwrap();
+        acc.apply(Transaction::Dispute(Tx::from(1))).unwrap();
+        acc.apply(Transaction::Chargeback(Tx::from(1))).unwrap();
+        assert!(acc.locked);
+
+        assert_eq!(
+            acc.apply(Transaction::Deposit(Tx::from(2), amount, CurrencyId::base())),
+            Err(Error::AccountLocked(ClientId::from(1))),
+        );
+    }
+
+    #[test]
+    fn test_locked_account_rejects_withdrawal() {
+        let mut acc = Account::new(ClientId::from(1));
+        let amount = Amount::try_from(10.0).unwrap();
+        acc.apply(Transaction::Deposit(Tx::from(1), amount, CurrencyId::base())).unwrap();
+        acc.apply(Transaction::Dispute(Tx::from(1))).unwrap();
+        acc.apply(Transaction::Chargeback(Tx::from(1))).unwrap();
+        assert!(acc.locked);
+
+        assert_eq!(
+            acc.apply(Transaction::Withdrawal(Tx::from(2), amount, CurrencyId::base())),
+            Err(Error::AccountLocked(ClientId::from(1))),
+        );
+    }
+
+    #[test]
+    fn test_locked_account_still_allows_resolve() {
+        let mut acc = Account::new(ClientId::from(1));
+        let amount = Amount::try_from(10.0).unwrap();
+        acc.apply(Transaction::Deposit(Tx::from(1), amount, CurrencyId::base())).unwrap();
+        acc.apply(Transaction::Deposit(Tx::from(2), amount, CurrencyId::base())).unwrap();
+        acc.apply(Transaction::Dispute(Tx::from(1))).unwrap();
+        acc.apply(Transaction::Dispute(Tx::from(2))).unwrap();
+        acc.apply(Transaction::Chargeback(Tx::from(1))).unwrap();
+        assert!(acc.locked);
+
+        assert!(acc.apply(Transaction::Resolve(Tx::from(2))).is_ok());
+    }
+
+    #[test]
+    fn test_dispute_unknown_tx_is_an_error() {
+        let mut acc = Account::new(ClientId::from(1));
+
+        assert_eq!(
+            acc.apply(Transaction::Dispute(Tx::from(999))),
+            Err(Error::UnknownTx(Tx::from(999))),
+        );
+    }
+
+    #[test]
+    fn test_resolve_unknown_tx_is_an_error() {
+        let mut acc = Account::new(ClientId::from(1));
+
+        assert_eq!(
+            acc.apply(Transaction::Resolve(Tx::from(999))),
+            Err(Error::UnknownTx(Tx::from(999))),
+        );
+    }
+
+    #[test]
+    fn test_chargeback_unknown_tx_is_an_error() {
+        let mut acc = Account::new(ClientId::from(1));
+
+        assert_eq!(
+            acc.apply(Transaction::Chargeback(Tx::from(999))),
+            Err(Error::UnknownTx(Tx::from(999))),
+        );
+    }
+
+    #[test]
+    fn test_disputing_an_already_disputed_tx_is_an_error() {
+        let mut acc = Account::new(ClientId::from(1));
+        let amount = Amount::try_from(10.0).unwrap();
+        acc.apply(Transaction::Deposit(Tx::from(1), amount, CurrencyId::base())).unwrap();
+        acc.apply(Transaction::Dispute(Tx::from(1))).unwrap();
+
+        assert_eq!(
+            acc.apply(Transaction::Dispute(Tx::from(1))),
+            Err(Error::AlreadyDisputed(Tx::from(1))),
+        );
+    }
+
+    #[test]
+    fn test_resolving_a_tx_that_was_never_disputed_is_an_error() {
+        let mut acc = Account::new(ClientId::from(1));
+        let amount = Amount::try_from(10.0).unwrap();
+        acc.apply(Transaction::Deposit(Tx::from(1), amount, CurrencyId::base())).unwrap();
+
+        assert_eq!(
+            acc.apply(Transaction::Resolve(Tx::from(1))),
+            Err(Error::NotDisputed(Tx::from(1))),
+        );
+    }
+
+    #[test]
+    fn test_charging_back_a_tx_that_was_never_disputed_is_an_error() {
+        let mut acc = Account::new(ClientId::from(1));
+        let amount = Amount::try_from(10.0).unwrap();
+        acc.apply(Transaction::Deposit(Tx::from(1), amount, CurrencyId::base())).unwrap();
+
+        assert_eq!(
+            acc.apply(Transaction::Chargeback(Tx::from(1))),
+            Err(Error::NotDisputed(Tx::from(1))),
+        );
+    }
+
+    #[test]
+    fn test_resolving_an_already_resolved_tx_is_an_error() {
+        let mut acc = Account::new(ClientId::from(1));
+        let amount = Amount::try_from(10.0).unwrap();
+        acc.apply(Transaction::Deposit(Tx::from(1), amount, CurrencyId::base())).unwrap();
+        acc.apply(Transaction::Dispute(Tx::from(1))).unwrap();
+        acc.apply(Transaction::Resolve(Tx::from(1))).unwrap();
+
+        assert_eq!(
+            acc.apply(Transaction::Resolve(Tx::from(1))),
+            Err(Error::NotDisputed(Tx::from(1))),
+        );
+    }
+
+    #[test]
+    fn test_many_deposits_and_disputes() {
+        let mut acc = Account::new(ClientId::from(1));
+        let amount = Amount::try_from(1.0).unwrap();
+        let count = 50_000u32;
+
+        for tx in 0..count {
+            acc.apply(Transaction::Deposit(Tx::from(tx), amount, CurrencyId::base())).unwrap();
+        }
+        for tx in 0..count {
+            acc.apply(Transaction::Dispute(Tx::from(tx))).unwrap();
+        }
+
+        let (available, held) = base_balance(&acc);
+        assert_eq!(available, Amount::new());
+        assert_eq!(held, Amount::try_from(count as f64).unwrap());
+    }
+
+    #[test]
+    fn test_dispute_withdrawal_holds_without_touching_available() {
+        let mut acc = Account::new(ClientId::from(1));
+        let deposit = Amount::try_from(50.0).unwrap();
+        let withdrawal = Amount::try_from(20.0).unwrap();
+        acc.apply(Transaction::Deposit(Tx::from(1), deposit, CurrencyId::base())).unwrap();
+        acc.apply(Transaction::Withdrawal(Tx::from(2), withdrawal, CurrencyId::base())).unwrap();
+
+        acc.apply(Transaction::Dispute(Tx::from(2))).unwrap();
+
+        let (available, held) = base_balance(&acc);
+        assert_eq!(available, Amount::try_from(30.0).unwrap());
+        assert_eq!(held, withdrawal);
+    }
+
+    #[test]
+    fn test_resolve_disputed_withdrawal_leaves_available_unchanged() {
+        let mut acc = Account::new(ClientId::from(1));
+        let deposit = Amount::try_from(50.0).unwrap();
+        let withdrawal = Amount::try_from(20.0).unwrap();
+        acc.apply(Transaction::Deposit(Tx::from(1), deposit, CurrencyId::base())).unwrap();
+        acc.apply(Transaction::Withdrawal(Tx::from(2), withdrawal, CurrencyId::base())).unwrap();
+        acc.apply(Transaction::Dispute(Tx::from(2))).unwrap();
+
+        acc.apply(Transaction::Resolve(Tx::from(2))).unwrap();
+
+        let (available, held) = base_balance(&acc);
+        assert_eq!(available, Amount::try_from(30.0).unwrap());
+        assert_eq!(held, Amount::new());
+    }
+
+    #[test]
+    fn test_chargeback_disputed_withdrawal_credits_available_and_locks() {
+        let mut acc = Account::new(ClientId::from(1));
+        let deposit = Amount::try_from(50.0).unwrap();
+        let withdrawal = Amount::try_from(20.0).unwrap();
+        acc.apply(Transaction::Deposit(Tx::from(1), deposit, CurrencyId::base())).unwrap();
+        acc.apply(Transaction::Withdrawal(Tx::from(2), withdrawal, CurrencyId::base())).unwrap();
+        acc.apply(Transaction::Dispute(Tx::from(2))).unwrap();
+
+        acc.apply(Transaction::Chargeback(Tx::from(2))).unwrap();
+
+        let (available, held) = base_balance(&acc);
+        assert_eq!(available, Amount::try_from(50.0).unwrap());
+        assert_eq!(held, Amount::new());
+        assert!(acc.locked);
+    }
+
+    #[test]
+    fn test_balances_are_kept_separate_per_currency() {
+        let mut acc = Account::new(ClientId::from(1));
+        let btc = CurrencyId::from("BTC".to_string());
+        acc.apply(Transaction::Deposit(Tx::from(1), Amount::try_from(10.0).unwrap(), CurrencyId::base())).unwrap();
+        acc.apply(Transaction::Deposit(Tx::from(2), Amount::try_from(1.5).unwrap(), btc.clone())).unwrap();
+
+        let balances = acc.balances();
+        let base = balances.iter().find(|(currency, _, _)| *currency == CurrencyId::base()).unwrap();
+        let btc_balance = balances.iter().find(|(currency, _, _)| *currency == btc).unwrap();
+        assert_eq!(base.1, Amount::try_from(10.0).unwrap());
+        assert_eq!(btc_balance.1, Amount::try_from(1.5).unwrap());
+    }
+
+    #[test]
+    fn test_new_account_reports_a_zero_balance_row_even_with_no_transactions() {
+        let acc = Account::new(ClientId::from(1));
+
+        let balances = acc.balances();
+        assert_eq!(balances, vec![(CurrencyId::base(), Amount::new(), Amount::new())]);
+    }
 }
@@ -8,16 +8,29 @@ use std::iter::Iterator;
 use super::model::*;
 
 
-/// Storage service for client accounts.
-pub struct AccountStorage {
+/// A pluggable backend for storing and retrieving client accounts.
+///
+/// This is the extension point for swapping the in-memory implementation
+/// for one that can spill to disk, once the account set no longer fits
+/// in RAM, without touching the CSV driver or the domain model.
+pub trait AccountStore {
+    /// Applies a single transaction to the correct client account.
+    fn apply_transaction(&mut self, client_id: &ClientId, tx: Transaction) -> Result<()>;
+
+    /// Returns an iterator over all known accounts.
+    fn iter(&self) -> Box<dyn Iterator<Item = (&ClientId, &Account)> + '_>;
+}
+
+/// In-memory account store, backed by a hash map.
+pub struct MemAccountStore {
     /// The store uses a hash map for fast access to accounts.
     accounts: HashMap<ClientId, Account>,
 }
 
-impl AccountStorage {
-    /// Creates an empty account storage.
+impl MemAccountStore {
+    /// Creates an empty account store.
     pub fn new() -> Self {
-        AccountStorage {
+        MemAccountStore {
             accounts: HashMap::new(),
         }
     }
@@ -31,28 +44,15 @@ impl AccountStorage {
 
         self.accounts.get_mut(client_id).unwrap()
     }
+}
 
-    /// Apply a single transaction to the correct client account.
-    pub fn apply_transaction(&mut self, client_id: &ClientId, tx: Transaction) -> Result<()> {
+impl AccountStore for MemAccountStore {
+    fn apply_transaction(&mut self, client_id: &ClientId, tx: Transaction) -> Result<()> {
         let acc = self.get_client_account(client_id);
         acc.apply(tx)
     }
 
-    /// Return an iterator over all user accounts.
-    pub fn iter(&self) -> AccountStorageIter {
-        AccountStorageIter { iter: self.accounts.iter() }
-    }
-}
-
-
-pub struct AccountStorageIter<'a> {
-    iter: std::collections::hash_map::Iter<'a, ClientId, Account>,
-}
-
-impl<'a> Iterator for AccountStorageIter<'a> {
-    type Item = (&'a ClientId, &'a Account);
-
-    fn next(&mut self) -> Option<(&'a ClientId, &'a Account)> {
-        self.iter.next()
+    fn iter(&self) -> Box<dyn Iterator<Item = (&ClientId, &Account)> + '_> {
+        Box::new(self.accounts.iter())
     }
 }
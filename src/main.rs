@@ -7,8 +7,8 @@ use std::env;
 use std::error::Error;
 use serde::Deserialize;
 
-use account::model::{Amount, ClientId, Transaction, Tx};
-use account::service::AccountStorage;
+use account::model::{Amount, ClientId, CurrencyId, Error as ModelError, Transaction, Tx};
+use account::service::{AccountStore, MemAccountStore};
 
 
 #[derive(Debug, Deserialize)]
@@ -18,9 +18,10 @@ struct TransactionRow {
     client: u16,
     tx: u32,
     amount: Option<f64>,
+    currency: Option<String>,
 }
 
-fn process_input(storage: &mut AccountStorage, source_csv: &str) -> Result<(), Box<dyn Error>> {
+fn process_input(storage: &mut dyn AccountStore, source_csv: &str) -> Result<(), Box<dyn Error>> {
     let mut reader = csv::ReaderBuilder::new()
         .flexible(true)
         .trim(csv::Trim::All)
@@ -30,11 +31,12 @@ fn process_input(storage: &mut AccountStorage, source_csv: &str) -> Result<(), B
         eprintln!("{:?}", row);
         let client_id = ClientId::from(row.client);
         let tx_id = Tx::from(row.tx);
+        let currency = row.currency.map(CurrencyId::from).unwrap_or_else(CurrencyId::base);
         let tx = match row.kind.as_str() {
             "deposit" => {
                 if let Some(amount) = row.amount {
                     let amount = Amount::try_from(amount)?;
-                    Ok(Transaction::Deposit(tx_id, amount))
+                    Ok(Transaction::Deposit(tx_id, amount, currency))
                 } else {
                     Err("Deposit transaction is missing an amount")
                 }
@@ -42,7 +44,7 @@ fn process_input(storage: &mut AccountStorage, source_csv: &str) -> Result<(), B
             "withdrawal" => {
                 if let Some(amount) = row.amount {
                     let amount = Amount::try_from(amount)?;
-                    Ok(Transaction::Withdrawal(tx_id, amount))
+                    Ok(Transaction::Withdrawal(tx_id, amount, currency))
                 } else {
                     Err("Withdrawal transaction is missing an amount")
                 }
@@ -62,6 +64,10 @@ fn process_input(storage: &mut AccountStorage, source_csv: &str) -> Result<(), B
         }?;
 
         match storage.apply_transaction(&client_id, tx) {
+            Err(ModelError::UnknownTx(tx)) => eprintln!("Transaction {:?} references unknown transaction {:?}", row.tx, tx),
+            Err(ModelError::AlreadyDisputed(tx)) => eprintln!("Transaction {:?} is already disputed ({:?})", row.tx, tx),
+            Err(ModelError::NotDisputed(tx)) => eprintln!("Transaction {:?} is not under dispute ({:?})", row.tx, tx),
+            Err(ModelError::AccountLocked(client)) => eprintln!("Transaction {:?} rejected: account {:?} is locked", row.tx, client),
             Err(err) => eprintln!("Could not process transaction {}: {}", row.tx, err),
             _ => (),
         }
@@ -70,18 +76,21 @@ fn process_input(storage: &mut AccountStorage, source_csv: &str) -> Result<(), B
     Ok(())
 }
 
-fn print_output(storage: &AccountStorage) -> Result<(), Box<dyn Error>> {
+fn print_output(storage: &dyn AccountStore) -> Result<(), Box<dyn Error>> {
     let mut writer = csv::Writer::from_writer(std::io::stdout());
 
-    writer.write_record(&["client", "available", "held", "total", "locked"])?;
+    writer.write_record(&["client", "currency", "available", "held", "total", "locked"])?;
     for (client_id, account) in storage.iter() {
-        let total_amt = account.available.add(account.held)?;
-        let client = format!("{}", client_id);
-        let available = format!("{}", account.available);
-        let held = format!("{}", account.held);
-        let locked = format!("{}", account.locked);
-        let total = format!("{}", total_amt);
-        writer.write_record(&[client, available, held, total, locked])?;
+        for (currency, available_amt, held_amt) in account.balances() {
+            let total_amt = available_amt.add(held_amt)?;
+            let client = format!("{}", client_id);
+            let currency = format!("{}", currency);
+            let available = format!("{}", available_amt);
+            let held = format!("{}", held_amt);
+            let locked = format!("{}", account.locked);
+            let total = format!("{}", total_amt);
+            writer.write_record(&[client, currency, available, held, total, locked])?;
+        }
     }
     writer.flush()?;
 
@@ -89,7 +98,7 @@ fn print_output(storage: &AccountStorage) -> Result<(), Box<dyn Error>> {
 }
 
 fn main() {
-    let mut storage = AccountStorage::new();
+    let mut storage = MemAccountStore::new();
 
     let mut args = env::args();
     if args.len() == 2 {